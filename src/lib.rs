@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::iter;
 
 struct BlockSpec {
@@ -20,6 +21,130 @@ impl BlockSpec {
     }
 }
 
+/// A line configuration as a packed bitmask: bit `i` is set when cell `i` is
+/// filled. Lines up to 128 cells stay inline in a `u128`; wider lines spill to a
+/// little-endian word vector. The line's width is tracked by the owner (a
+/// `BlockSpec`, or `RowConfig::len`), so it is not stored here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LineMask {
+    Inline(u128),
+    Wide(Vec<u64>),
+}
+
+impl LineMask {
+    fn zeros(width: usize) -> Self {
+        if width <= 128 {
+            LineMask::Inline(0)
+        } else {
+            LineMask::Wide(vec![0u64; width.div_ceil(64)])
+        }
+    }
+
+    /// A mask with the low `width` bits set and everything above them clear.
+    fn ones(width: usize) -> Self {
+        if width <= 128 {
+            let m = if width == 128 {
+                u128::MAX
+            } else {
+                (1u128 << width) - 1
+            };
+            LineMask::Inline(m)
+        } else {
+            let n_words = width.div_ceil(64);
+            let mut words = vec![u64::MAX; n_words];
+            let rem = width % 64;
+            if rem != 0 {
+                words[n_words - 1] = (1u64 << rem) - 1;
+            }
+            LineMask::Wide(words)
+        }
+    }
+
+    fn set(&mut self, i: usize) {
+        match self {
+            LineMask::Inline(m) => *m |= 1u128 << i,
+            LineMask::Wide(words) => words[i / 64] |= 1u64 << (i % 64),
+        }
+    }
+
+    fn get(&self, i: usize) -> bool {
+        match self {
+            LineMask::Inline(m) => (*m >> i) & 1 == 1,
+            LineMask::Wide(words) => (words[i / 64] >> (i % 64)) & 1 == 1,
+        }
+    }
+
+    fn and_assign(&mut self, other: &LineMask) {
+        match (self, other) {
+            (LineMask::Inline(a), LineMask::Inline(b)) => *a &= *b,
+            (LineMask::Wide(a), LineMask::Wide(b)) => {
+                a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x &= *y)
+            }
+            _ => unreachable!("mismatched line mask widths"),
+        }
+    }
+
+    fn or_assign(&mut self, other: &LineMask) {
+        match (self, other) {
+            (LineMask::Inline(a), LineMask::Inline(b)) => *a |= *b,
+            (LineMask::Wide(a), LineMask::Wide(b)) => {
+                a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x |= *y)
+            }
+            _ => unreachable!("mismatched line mask widths"),
+        }
+    }
+
+    /// `true` when every bit set in `other` is also set in `self`.
+    fn contains(&self, other: &LineMask) -> bool {
+        match (self, other) {
+            (LineMask::Inline(a), LineMask::Inline(b)) => *b & !*a == 0,
+            (LineMask::Wide(a), LineMask::Wide(b)) => {
+                a.iter().zip(b.iter()).all(|(x, y)| *y & !*x == 0)
+            }
+            _ => unreachable!("mismatched line mask widths"),
+        }
+    }
+
+    /// `true` when `self` and `other` share at least one set bit.
+    fn intersects(&self, other: &LineMask) -> bool {
+        match (self, other) {
+            (LineMask::Inline(a), LineMask::Inline(b)) => *a & *b != 0,
+            (LineMask::Wide(a), LineMask::Wide(b)) => {
+                a.iter().zip(b.iter()).any(|(x, y)| *x & *y != 0)
+            }
+            _ => unreachable!("mismatched line mask widths"),
+        }
+    }
+}
+
+/// The cells a line solver has already pinned, split into a filled and an empty
+/// mask so a candidate config can be vetted with two bitwise ops.
+struct KnownLine {
+    filled: LineMask,
+    empty: LineMask,
+}
+
+impl KnownLine {
+    /// Build the known-cell masks for a line of the given width.
+    fn from_cells(cells: &[Cell], width: usize) -> Self {
+        let mut filled = LineMask::zeros(width);
+        let mut empty = LineMask::zeros(width);
+        for (i, c) in cells.iter().enumerate() {
+            match c {
+                Cell::Filled => filled.set(i),
+                Cell::Empty => empty.set(i),
+                Cell::Unknown => {}
+            }
+        }
+        KnownLine { filled, empty }
+    }
+
+    /// `true` if `cfg` fills every known-filled cell and no known-empty cell.
+    fn accepts(&self, cfg: &LineMask) -> bool {
+        cfg.contains(&self.filled) && !cfg.intersects(&self.empty)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 enum ColumnConfigPhase {
     Starting,
@@ -88,17 +213,17 @@ impl<'a> BlockSpecIterator<'a> {
             .enumerate()
             .zip(self.spec.block_sizes.iter())
             .flat_map(|((i, &s), &t)| {
-                iter::repeat(false)
-                    .take(if i == 0 { s } else { s + 1 })
-                    .chain(iter::repeat(true).take(t))
+                iter::repeat_n(false, if i == 0 { s } else { s + 1 })
+                    .chain(iter::repeat_n(true, t))
             })
-            .chain(iter::repeat(false).take(n_remain))
+            .chain(iter::repeat_n(false, n_remain))
     }
 }
 
 impl<'a> Iterator for BlockSpecIterator<'a> {
-    // type Item = impl Iterator<Item=bool> + 'a;  this is not stable, so collect
-    type Item = Vec<bool>;
+    // Each configuration is packed straight into a `LineMask`, avoiding the
+    // per-config `Vec<bool>` allocation the iterator used to collect.
+    type Item = LineMask;
     fn next(&mut self) -> Option<Self::Item> {
         match self.phase {
             ColumnConfigPhase::Starting => {
@@ -112,13 +237,19 @@ impl<'a> Iterator for BlockSpecIterator<'a> {
         if self.phase == ColumnConfigPhase::Exhausted {
             None
         } else {
-            Some(self.make_config_iter().collect())
+            let mut mask = LineMask::zeros(self.spec.size);
+            for (i, filled) in self.make_config_iter().enumerate() {
+                if filled {
+                    mask.set(i);
+                }
+            }
+            Some(mask)
         }
     }
 }
 
 impl<'a> IntoIterator for &'a BlockSpec {
-    type Item = Vec<bool>;
+    type Item = LineMask;
     type IntoIter = BlockSpecIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -143,7 +274,7 @@ fn test_column_configs() {
 }
 
 #[allow(dead_code)]
-fn all_configs(bs: &BlockSpec) -> Vec<Vec<bool>> {
+fn all_configs(bs: &BlockSpec) -> Vec<LineMask> {
     bs.into_iter().collect()
 }
 #[test]
@@ -159,15 +290,152 @@ pub struct Puzzle {
     vertical: Vec<Vec<usize>>,
 }
 
-/// first entry of row is most recent output. return a slice starting with output to satisfy col
+/// Which set of clues a validation error refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Axis::Horizontal => write!(f, "horizontal"),
+            Axis::Vertical => write!(f, "vertical"),
+        }
+    }
+}
+
+/// A `Puzzle` whose clues cannot be placed in the grid it describes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PuzzleError {
+    /// A clue line needs more cells than its axis provides. `required` is the
+    /// minimum run length (`sum + blocks.len() - 1`) and `available` the line size.
+    LineTooLong {
+        axis: Axis,
+        index: usize,
+        required: usize,
+        available: usize,
+    },
+}
+
+impl fmt::Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PuzzleError::LineTooLong {
+                axis,
+                index,
+                required,
+                available,
+            } => write!(
+                f,
+                "{} line {} needs at least {} cells but the line is only {} wide",
+                axis, index, required, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PuzzleError {}
+
+impl Puzzle {
+    /// Check that every clue line fits in its axis, mirroring the feasibility
+    /// condition `BlockSpec::new` would otherwise `panic!` on.
+    fn validate(&self) -> Result<(), PuzzleError> {
+        let n_row = self.horizontal.len();
+        let n_col = self.vertical.len();
+        for (index, blocks) in self.horizontal.iter().enumerate() {
+            check_line(Axis::Horizontal, index, blocks, n_col)?;
+        }
+        for (index, blocks) in self.vertical.iter().enumerate() {
+            check_line(Axis::Vertical, index, blocks, n_row)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_line(axis: Axis, index: usize, blocks: &[usize], size: usize) -> Result<(), PuzzleError> {
+    // The blocks plus one separating space between each neighbouring pair.
+    let required = blocks.iter().sum::<usize>() + blocks.len().saturating_sub(1);
+    // `BlockSpec::new` takes `(size + 1) - (blocks.len() + sum)`, so it is
+    // feasible exactly when `required <= size`.
+    if required > size {
+        return Err(PuzzleError::LineTooLong {
+            axis,
+            index,
+            required,
+            available: size,
+        });
+    }
+    Ok(())
+}
+
+/// A solved grid plus the diagnostics gathered while solving it.
+#[derive(Debug, Serialize)]
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    /// One string per row, each cell rendered as `'#'` (filled) or `'.'` (empty).
+    pub rows: Vec<String>,
+    /// `true` when constraint propagation alone determined the grid, with no
+    /// fallback search.
+    pub propagation_only: bool,
+    /// Number of search nodes (`WorkItem`s) expanded; `0` when solved purely by
+    /// propagation.
+    pub nodes_visited: usize,
+}
+
+impl Grid {
+    fn from_cells(cells: &[Vec<bool>], propagation_only: bool, nodes_visited: usize) -> Self {
+        let height = cells.len();
+        let width = cells.first().map_or(0, |row| row.len());
+        let rows = cells
+            .iter()
+            .map(|row| row.iter().map(|&b| if b { '#' } else { '.' }).collect())
+            .collect();
+        Grid {
+            width,
+            height,
+            rows,
+            propagation_only,
+            nodes_visited,
+        }
+    }
+}
+
+/// Outcome of solving a puzzle, suitable for serialization by the CLI.
+#[derive(Debug, Serialize)]
+pub enum SolveOutcome {
+    Solved(Grid),
+    Ambiguous { count: usize, example: Grid },
+    Unsolvable,
+}
+
+/// The sentinel bit-sequence that [`advance_row`] walks as the search places one
+/// column at a time: a leading space, the line's block pattern, then a trailing
+/// space. Packed into a [`LineMask`] of `len` bits; the search threads a position
+/// into it rather than reslicing a `Vec<bool>`.
+struct RowConfig {
+    bits: LineMask,
+    len: usize,
+}
+
+/// `pos` indexes the most recent output in `rc`. Advance one column's worth and
+/// return the new position satisfying `col`.
 /// remaining column count is number of columns to output after this
-fn advance_row(row: &[bool], col: bool, remaining_column_count: usize) -> Result<&[bool], ()> {
-    let r0 = row[0];
+fn advance_row(
+    rc: &RowConfig,
+    pos: usize,
+    col: bool,
+    remaining_column_count: usize,
+) -> Result<usize, ()> {
+    let r0 = rc.bits.get(pos);
+    let remaining = rc.len - pos;
     if r0 {
         // last output was a mark and we are forced to advance.
-        // row[1] is always defined in this case
-        if row[1] == col {
-            Ok(&row[1..])
+        // bit pos+1 is always defined in this case
+        if rc.bits.get(pos + 1) == col {
+            Ok(pos + 1)
         } else {
             Err(())
         }
@@ -176,15 +444,15 @@ fn advance_row(row: &[bool], col: bool, remaining_column_count: usize) -> Result
         if col {
             // must output mark.
             // next entry will always be mark -- if it exists
-            if row.len() > 1 {
-                Ok(&row[1..])
+            if remaining > 1 {
+                Ok(pos + 1)
             } else {
                 Err(())
             }
         } else {
             // not advancing. check remaining column count
-            if row.len() <= remaining_column_count + 2 {
-                Ok(row)
+            if remaining <= remaining_column_count + 2 {
+                Ok(pos)
             } else {
                 Err(())
             }
@@ -194,112 +462,504 @@ fn advance_row(row: &[bool], col: bool, remaining_column_count: usize) -> Result
 
 #[test]
 fn test_advance_row() {
-    let rr = vec![false, true, true, false, true, false];
-    assert_eq!(advance_row(&rr[..], true, 10), Ok(&rr[1..]));
-    assert_eq!(advance_row(&rr[..], false, 4), Ok(&rr[..]));
-    assert_eq!(advance_row(&rr[..], false, 3), Err(())); // fail on column count
-
-    assert_eq!(advance_row(&rr[1..], true, 10), Ok(&rr[2..]));
-    assert_eq!(advance_row(&rr[1..], false, 10), Err(()));
-    assert_eq!(advance_row(&rr[2..], true, 10), Err(()));
-    assert_eq!(advance_row(&rr[2..], false, 10), Ok(&rr[3..]));
-}
-
-fn solve_recursive(row_configs: Vec<&[bool]>, cols: &[BlockSpec]) -> Option<Vec<Vec<bool>>> {
-    println!("Recursive solve called for length {}", cols.len());
-    if cols.len() == 0 {
-        return Some(Vec::new());
-    };
-    let col = &cols[0];
-    let rest = &cols[1..];
-    for cfg in col {
-        if let Ok(next_row_configs) = cfg
-            .iter()
-            .zip(row_configs.iter())
-            .map(|(c, row)| advance_row(row, *c, rest.len()))
-            .collect()
-        {
-            if let Some(mut sol) = solve_recursive(next_row_configs, rest) {
-                sol.push(cfg);
-                return Some(sol);
-            }
-        };
+    // Bits [false, true, true, false, true, false].
+    let mut bits = LineMask::zeros(6);
+    for i in [1, 2, 4] {
+        bits.set(i);
     }
-    None
+    let rc = RowConfig { bits, len: 6 };
+    assert_eq!(advance_row(&rc, 0, true, 10), Ok(1));
+    assert_eq!(advance_row(&rc, 0, false, 4), Ok(0));
+    assert_eq!(advance_row(&rc, 0, false, 3), Err(())); // fail on column count
+
+    assert_eq!(advance_row(&rc, 1, true, 10), Ok(2));
+    assert_eq!(advance_row(&rc, 1, false, 10), Err(()));
+    assert_eq!(advance_row(&rc, 2, true, 10), Err(()));
+    assert_eq!(advance_row(&rc, 2, false, 10), Ok(3));
 }
 
 struct WorkItem<'a> {
-    row_configs: Vec<&'a [bool]>,
+    row_pos: Vec<usize>,
     cols: &'a [BlockSpec],
-    sol: Vec<Vec<bool>>,
+    sol: Vec<LineMask>,
 }
 
-fn check_config<'a, 'b>(cfg: Vec<bool>, row_configs: &'a Vec<&'b [bool]>, remaining_column_count: usize) -> Option<(Vec<bool>, Vec<&'b [bool]>)> {
-    //let r: Option<Vec<&[bool]>> = 
-    cfg
-    .iter()
-    .zip(row_configs.iter())
-    .map(|(c, row)| advance_row(row, *c, remaining_column_count))
-    .collect::<Result<Vec<_>,()>>()
-    .ok()
-    .and_then(|c| Some((cfg, c)))
+fn check_config(
+    cfg: LineMask,
+    row_configs: &[RowConfig],
+    row_pos: &[usize],
+    remaining_column_count: usize,
+) -> Option<(LineMask, Vec<usize>)> {
+    row_configs
+        .iter()
+        .zip(row_pos.iter())
+        .enumerate()
+        .map(|(i, (rc, &pos))| advance_row(rc, pos, cfg.get(i), remaining_column_count))
+        .collect::<Result<Vec<_>, ()>>()
+        .ok()
+        .map(|c| (cfg, c))
 }
 
-fn solve_stacked(row_configs: Vec<&[bool]>, cols: &[BlockSpec]) -> Option<Vec<Vec<bool>>> {
-    let mut work = vec![WorkItem{ row_configs, cols, sol: Vec::new() }];
-    loop {
-        if let Some(w) = work.pop() {
-            if w.cols.len()<1 {
-                return Some(w.sol);
-            };
-            let col = &w.cols[0];
-            let rest = &w.cols[1..];
-            let remaining_column_count = rest.len();
-            println!("Current work item has {} open columns", remaining_column_count);
-            work.extend(
-                col
-                .into_iter()
-                .filter_map(|cfg| check_config(cfg, &w.row_configs, remaining_column_count))
-                .map(|(col_cfg, new_row_configs)| WorkItem {
-                    row_configs: new_row_configs, 
-                    cols: rest,
-                    sol: w.sol.iter().cloned().chain(iter::once(col_cfg)).collect(),
-                })
-            );
-        } else {
-            return None;
+/// Expand one `WorkItem` by placing its first open column: yields a child item
+/// per column configuration that both agrees with the propagated cells and
+/// extends every row config. `n_col` is the total column count, used to index
+/// `known_cols` from the remaining-column suffix carried by `w`.
+fn expand<'a>(
+    w: &WorkItem<'a>,
+    n_col: usize,
+    row_configs: &[RowConfig],
+    known_cols: &[KnownLine],
+) -> Vec<WorkItem<'a>> {
+    let col = &w.cols[0];
+    let rest = &w.cols[1..];
+    let remaining_column_count = rest.len();
+    let known = &known_cols[n_col - w.cols.len()];
+    col.into_iter()
+        .filter(|cfg| known.accepts(cfg))
+        .filter_map(|cfg| check_config(cfg, row_configs, &w.row_pos, remaining_column_count))
+        .map(|(col_cfg, new_row_pos)| WorkItem {
+            row_pos: new_row_pos,
+            cols: rest,
+            sol: w.sol.iter().cloned().chain(iter::once(col_cfg)).collect(),
+        })
+        .collect()
+}
+
+/// Enumerate complete solutions reachable from the seeded work stack. A
+/// completed `WorkItem` is pushed onto a results vector rather than returned,
+/// and the stack keeps draining. A non-zero `limit` stops
+/// the search once that many distinct solutions have been collected. The second
+/// tuple element is the number of nodes (`WorkItem`s) expanded.
+fn solve_all_stacked(
+    row_configs: &[RowConfig],
+    cols: &[BlockSpec],
+    known_cols: &[KnownLine],
+    limit: usize,
+) -> (Vec<Vec<LineMask>>, usize) {
+    let n_col = cols.len();
+    let mut results: Vec<Vec<LineMask>> = Vec::new();
+    let mut nodes = 0usize;
+    let mut work = vec![WorkItem {
+        row_pos: vec![0; row_configs.len()],
+        cols,
+        sol: Vec::new(),
+    }];
+    while let Some(w) = work.pop() {
+        if w.cols.is_empty() {
+            results.push(w.sol);
+            if limit > 0 && results.len() >= limit {
+                break;
+            }
+            continue;
         }
-   }
+        nodes += 1;
+        work.extend(expand(&w, n_col, row_configs, known_cols));
+    }
+    (results, nodes)
 }
 
-fn make_row_config(bs: &BlockSpec) -> Vec<bool> {
+/// Build the sentinel bit-sequence [`advance_row`] walks for a line: a leading
+/// space, the config pattern, and a trailing space, packed into a [`RowConfig`].
+fn make_row_config(bs: &BlockSpec) -> RowConfig {
     let cc = BlockSpecIterator::new(bs);
-    iter::once(false)
-        .chain(cc.make_config_iter().take(bs.size - cc.remaining_spaces()))
-        .chain(iter::once(false))
-        .collect()
+    let n = bs.size - cc.remaining_spaces();
+    let mut bits = LineMask::zeros(n + 2);
+    // index 0 is the leading space; config bits land at 1..=n; index n+1 is the
+    // trailing space. Spaces stay clear, so only the filled cells are set.
+    for (k, filled) in cc.make_config_iter().take(n).enumerate() {
+        if filled {
+            bits.set(k + 1);
+        }
+    }
+    RowConfig { bits, len: n + 2 }
 }
 
 #[test]
 fn test_make_row_config() {
     let rc = make_row_config(&BlockSpec::new(vec![2, 1], 10));
-    assert_eq!(rc, vec![false, true, true, false, true, false]);
+    assert_eq!(rc.len, 6);
+    let bits: Vec<bool> = (0..rc.len).map(|i| rc.bits.get(i)).collect();
+    assert_eq!(bits, vec![false, true, true, false, true, false]);
+}
+
+#[test]
+fn test_propagate_line_forces_cells() {
+    // A block of 4 in a line of 5 forces the middle three cells.
+    let spec = BlockSpec::new(vec![4], 5);
+    let mut line = vec![Cell::Unknown; 5];
+    assert_eq!(propagate_line(&spec, &mut line), Some(true));
+    assert_eq!(
+        line,
+        vec![
+            Cell::Unknown,
+            Cell::Filled,
+            Cell::Filled,
+            Cell::Filled,
+            Cell::Unknown
+        ]
+    );
+}
+
+#[test]
+fn test_solve_unique_by_propagation() {
+    // A fully-filled 2x2 grid is resolved by propagation alone.
+    let puzzle = Puzzle {
+        horizontal: vec![vec![2], vec![2]],
+        vertical: vec![vec![2], vec![2]],
+    };
+    assert!(solve_unique(puzzle));
+}
+
+#[test]
+fn test_count_solutions_ambiguous() {
+    // A 2x2 with one filled cell per row and column is satisfied by either
+    // diagonal, so the clues are ambiguous: counting up to 3 finds exactly 2.
+    let puzzle = Puzzle {
+        horizontal: vec![vec![1], vec![1]],
+        vertical: vec![vec![1], vec![1]],
+    };
+    assert_eq!(count_solutions(puzzle, 3), 2);
 }
 
-pub fn solve(puzzle: Puzzle) -> Option<Vec<Vec<bool>>> {
+#[test]
+fn test_reject_infeasible_clue() {
+    // A block of 3 cannot fit across a 2-column grid.
+    let puzzle = Puzzle {
+        horizontal: vec![vec![3]],
+        vertical: vec![vec![1], vec![1]],
+    };
+    assert!(matches!(
+        solve_outcome(puzzle),
+        Err(PuzzleError::LineTooLong {
+            axis: Axis::Horizontal,
+            index: 0,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_outcome_solved_by_propagation() {
+    let puzzle = Puzzle {
+        horizontal: vec![vec![2], vec![2]],
+        vertical: vec![vec![2], vec![2]],
+    };
+    match solve_outcome(puzzle) {
+        Ok(SolveOutcome::Solved(grid)) => {
+            assert!(grid.propagation_only);
+            assert_eq!(grid.nodes_visited, 0);
+            assert_eq!(grid.rows, vec!["##".to_string(), "##".to_string()]);
+        }
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+}
+
+#[test]
+fn test_outcome_ambiguous() {
+    let puzzle = Puzzle {
+        horizontal: vec![vec![1], vec![1]],
+        vertical: vec![vec![1], vec![1]],
+    };
+    assert!(matches!(
+        solve_outcome(puzzle),
+        Ok(SolveOutcome::Ambiguous { count: 2, .. })
+    ));
+}
+
+/// Known state of a single cell during line-solving constraint propagation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cell {
+    Unknown,
+    Filled,
+    Empty,
+}
+
+/// Restrict `spec`'s configurations to those consistent with the already-known
+/// cells of `line`, intersect the survivors, and write back any newly forced
+/// cells. A cell that is `true` in every surviving config becomes `Filled`, one
+/// that is `false` in every survivor becomes `Empty`.
+///
+/// Returns `None` when no configuration is consistent (a contradiction) and
+/// `Some(true)` when at least one `Unknown` cell was determined.
+fn propagate_line(spec: &BlockSpec, line: &mut [Cell]) -> Option<bool> {
+    let width = spec.size;
+    let known = KnownLine::from_cells(line, width);
+    let mut and_acc = LineMask::ones(width); // set where every survivor is filled
+    let mut or_acc = LineMask::zeros(width); // set where some survivor is filled
+    let mut any = false;
+    for cfg in spec {
+        if !known.accepts(&cfg) {
+            continue;
+        }
+        any = true;
+        and_acc.and_assign(&cfg);
+        or_acc.or_assign(&cfg);
+    }
+    if !any {
+        return None;
+    }
+    let mut changed = false;
+    for (i, cell) in line.iter_mut().enumerate() {
+        if *cell != Cell::Unknown {
+            continue;
+        }
+        if and_acc.get(i) {
+            *cell = Cell::Filled;
+            changed = true;
+        } else if !or_acc.get(i) {
+            *cell = Cell::Empty;
+            changed = true;
+        }
+    }
+    Some(changed)
+}
+
+/// Run the line solver over every row and column repeatedly until a full pass
+/// yields no new deductions. Returns the propagated grid, or `None` if any line
+/// is found to have no consistent configuration.
+fn propagate_grid(rows: &[BlockSpec], cols: &[BlockSpec]) -> Option<Vec<Vec<Cell>>> {
+    let n_row = rows.len();
+    let n_col = cols.len();
+    let mut grid = vec![vec![Cell::Unknown; n_col]; n_row];
+    loop {
+        let mut changed = false;
+        for (i, spec) in rows.iter().enumerate() {
+            changed |= propagate_line(spec, &mut grid[i])?;
+        }
+        for (j, spec) in cols.iter().enumerate() {
+            let mut column: Vec<Cell> = (0..n_row).map(|i| grid[i][j]).collect();
+            if propagate_line(spec, &mut column)? {
+                changed = true;
+                for (i, cell) in column.into_iter().enumerate() {
+                    grid[i][j] = cell;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    Some(grid)
+}
+
+/// Build the row and column `BlockSpec`s from a puzzle without consuming its
+/// dimensions twice.
+fn puzzle_specs(puzzle: Puzzle) -> (Vec<BlockSpec>, Vec<BlockSpec>) {
     let n_col = puzzle.vertical.len();
     let n_row = puzzle.horizontal.len();
-    let cols: Vec<_> = puzzle
+    let cols = puzzle
         .vertical
         .into_iter()
         .map(|bs| BlockSpec::new(bs, n_row))
         .collect();
-    let rows: Vec<_> = puzzle
+    let rows = puzzle
         .horizontal
         .into_iter()
         .map(|bs| BlockSpec::new(bs, n_col))
         .collect();
-    let row_configs: Vec<Vec<bool>> = rows.iter().map(make_row_config).collect();
-    //solve_recursive(row_configs.iter().map(|v| &v[..]).collect(), &cols)
-    solve_stacked(row_configs.iter().map(|v| &v[..]).collect(), &cols)
+    (rows, cols)
+}
+
+/// `true` when constraint propagation alone fully determines the puzzle, i.e. no
+/// `Unknown` cell remains after the fixpoint.
+pub fn solve_unique(puzzle: Puzzle) -> bool {
+    match solve(puzzle) {
+        Some(grid) => grid
+            .iter()
+            .all(|row| row.iter().all(|c| *c != Cell::Unknown)),
+        None => false,
+    }
+}
+
+/// `true` when every cell of a propagated grid is determined.
+fn grid_is_full(grid: &[Vec<Cell>]) -> bool {
+    grid.iter().all(|row| row.iter().all(|c| *c != Cell::Unknown))
+}
+
+/// Convert a fully-determined grid into the `bool` matrix used at the output
+/// boundary.
+fn grid_to_bools(grid: Vec<Vec<Cell>>) -> Vec<Vec<bool>> {
+    grid.into_iter()
+        .map(|row| row.into_iter().map(|c| c == Cell::Filled).collect())
+        .collect()
+}
+
+/// Per-column known-cell masks derived from the propagated grid, used to prune
+/// the seeded column search.
+fn known_column_masks(grid: &[Vec<Cell>], n_row: usize, n_col: usize) -> Vec<KnownLine> {
+    (0..n_col)
+        .map(|j| {
+            let column: Vec<Cell> = grid.iter().map(|row| row[j]).collect();
+            KnownLine::from_cells(&column, n_row)
+        })
+        .collect()
+}
+
+/// Reassemble the search result — a column per placed `LineMask`, each over
+/// `n_row` cells — into the row-major `bool` grid used at the output boundary.
+fn columns_to_grid(columns: &[LineMask], n_row: usize) -> Vec<Vec<bool>> {
+    (0..n_row)
+        .map(|i| columns.iter().map(|c| c.get(i)).collect())
+        .collect()
+}
+
+/// Run constraint propagation to a fixpoint and return the resulting partial
+/// grid. Most real puzzles collapse to a fully-determined grid here; the rest
+/// leave some cells `Unknown` for the enumerating search in [`solve_all`] to
+/// resolve. `None` signals a contradiction (the puzzle has no solution).
+pub fn solve(puzzle: Puzzle) -> Option<Vec<Vec<Cell>>> {
+    let (rows, cols) = puzzle_specs(puzzle);
+    propagate_grid(&rows, &cols)
+}
+
+/// Enumerate every solution of the puzzle. Propagation runs first to seed and
+/// prune the column search; a puzzle resolved by propagation alone yields a
+/// single solution.
+pub fn solve_all(puzzle: Puzzle) -> Vec<Vec<Vec<bool>>> {
+    solve_bounded(puzzle, 0)
+}
+
+/// Count distinct solutions, stopping as soon as `limit` have been found. Pass
+/// `limit = 2` to answer "is this puzzle uniquely solvable?" without paying for
+/// a full enumeration.
+pub fn count_solutions(puzzle: Puzzle, limit: usize) -> usize {
+    solve_bounded(puzzle, limit).len()
+}
+
+/// Shared driver for [`solve_all`] and [`count_solutions`]: propagate, then run
+/// the enumerating search bounded by `limit` (`0` means unbounded).
+fn solve_bounded(puzzle: Puzzle, limit: usize) -> Vec<Vec<Vec<bool>>> {
+    let (rows, cols) = puzzle_specs(puzzle);
+    let n_row = rows.len();
+    let grid = match propagate_grid(&rows, &cols) {
+        Some(g) => g,
+        None => return Vec::new(),
+    };
+    if grid_is_full(&grid) {
+        return vec![grid_to_bools(grid)];
+    }
+    let known_cols = known_column_masks(&grid, n_row, cols.len());
+    let row_configs: Vec<RowConfig> = rows.iter().map(make_row_config).collect();
+    let (solutions, _nodes) = solve_all_stacked(&row_configs, &cols, &known_cols, limit);
+    solutions
+        .into_iter()
+        .map(|columns| columns_to_grid(&columns, n_row))
+        .collect()
+}
+
+/// Solve a puzzle and report a structured outcome with diagnostics. Clues are
+/// validated first, so an infeasible puzzle returns a typed [`PuzzleError`]
+/// rather than panicking. At most two solutions are enumerated — enough to tell
+/// a unique solution from an ambiguous one.
+pub fn solve_outcome(puzzle: Puzzle) -> Result<SolveOutcome, PuzzleError> {
+    puzzle.validate()?;
+    let (rows, cols) = puzzle_specs(puzzle);
+    let n_row = rows.len();
+    let grid = match propagate_grid(&rows, &cols) {
+        Some(g) => g,
+        None => return Ok(SolveOutcome::Unsolvable),
+    };
+    if grid_is_full(&grid) {
+        let cells = grid_to_bools(grid);
+        return Ok(SolveOutcome::Solved(Grid::from_cells(&cells, true, 0)));
+    }
+    let known_cols = known_column_masks(&grid, n_row, cols.len());
+    let row_configs: Vec<RowConfig> = rows.iter().map(make_row_config).collect();
+    let (solutions, nodes) = solve_all_stacked(&row_configs, &cols, &known_cols, 2);
+    let mut grids: Vec<Vec<Vec<bool>>> = solutions
+        .into_iter()
+        .map(|columns| columns_to_grid(&columns, n_row))
+        .collect();
+    match grids.len() {
+        0 => Ok(SolveOutcome::Unsolvable),
+        1 => Ok(SolveOutcome::Solved(Grid::from_cells(
+            &grids.pop().unwrap(),
+            false,
+            nodes,
+        ))),
+        count => Ok(SolveOutcome::Ambiguous {
+            count,
+            example: Grid::from_cells(&grids[0], false, nodes),
+        }),
+    }
+}
+
+/// Number of independent `WorkItem`s to accumulate before handing the frontier
+/// to rayon. Below this the serial expansion overhead dominates.
+#[cfg(feature = "rayon")]
+const PARALLEL_SPLIT_THRESHOLD: usize = 16;
+
+/// Explore one self-contained `WorkItem` subtree with the serial stack search,
+/// bailing out early once another thread has signalled `cancel`.
+#[cfg(feature = "rayon")]
+fn search_cancellable(
+    item: WorkItem,
+    n_col: usize,
+    row_configs: &[RowConfig],
+    known_cols: &[KnownLine],
+    cancel: &std::sync::atomic::AtomicBool,
+) -> Option<Vec<LineMask>> {
+    use std::sync::atomic::Ordering;
+    let mut work = vec![item];
+    while let Some(w) = work.pop() {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        if w.cols.is_empty() {
+            return Some(w.sol);
+        }
+        work.extend(expand(&w, n_col, row_configs, known_cols));
+    }
+    None
+}
+
+/// Find one complete solution, searching in parallel. Propagation runs first
+/// and seeds the column search, as in [`solve_all`]. The explicit work stack
+/// is expanded serially until it holds enough independent `WorkItem`s, then
+/// their subtrees are
+/// explored concurrently via rayon. The first thread to complete a grid flips a
+/// shared cancellation flag so the rest abandon their subtrees.
+#[cfg(feature = "rayon")]
+pub fn solve_parallel(puzzle: Puzzle) -> Option<Vec<Vec<bool>>> {
+    use rayon::prelude::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    let (rows, cols) = puzzle_specs(puzzle);
+    let n_row = rows.len();
+    let grid = propagate_grid(&rows, &cols)?;
+    if grid_is_full(&grid) {
+        return Some(grid_to_bools(grid));
+    }
+    let n_col = cols.len();
+    let known_cols = known_column_masks(&grid, n_row, n_col);
+    let row_configs: Vec<RowConfig> = rows.iter().map(make_row_config).collect();
+
+    // Grow a frontier of independent subtrees before fanning out.
+    let mut frontier = vec![WorkItem {
+        row_pos: vec![0; row_configs.len()],
+        cols: &cols,
+        sol: Vec::new(),
+    }];
+    while frontier.len() < PARALLEL_SPLIT_THRESHOLD {
+        let w = frontier.pop()?;
+        if w.cols.is_empty() {
+            // Solved while still building the frontier.
+            return Some(columns_to_grid(&w.sol, n_row));
+        }
+        frontier.extend(expand(&w, n_col, &row_configs, &known_cols));
+    }
+
+    let cancel = AtomicBool::new(false);
+    let result: Mutex<Option<Vec<LineMask>>> = Mutex::new(None);
+    frontier.into_par_iter().for_each(|item| {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(sol) = search_cancellable(item, n_col, &row_configs, &known_cols, &cancel) {
+            cancel.store(true, Ordering::Relaxed);
+            *result.lock().unwrap() = Some(sol);
+        }
+    });
+    result.into_inner().unwrap().map(|columns| columns_to_grid(&columns, n_row))
 }
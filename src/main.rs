@@ -1,10 +1,39 @@
-use passion_rs::{Puzzle, solve};
+use passion_rs::{solve_outcome, Puzzle, SolveOutcome};
+use std::process::exit;
 
 fn main() {
     let args: Vec<_> = std::env::args().collect();
+    let prog = args.first().map(String::as_str).unwrap_or("nonogram");
+    if args.len() < 2 {
+        eprintln!("usage: {} <puzzle.json>", prog);
+        exit(2);
+    }
 
-    let puzzle: Puzzle = serde_json::from_str(&std::fs::read_to_string(&args[1]).unwrap()).unwrap();
-    println!("Puzzle: {:?}", puzzle);
-    let res = solve(puzzle);
-    println!("Solution: {:?}", res);
+    let text = match std::fs::read_to_string(&args[1]) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("could not read {}: {}", args[1], err);
+            exit(2);
+        }
+    };
+    let puzzle: Puzzle = match serde_json::from_str(&text) {
+        Ok(puzzle) => puzzle,
+        Err(err) => {
+            eprintln!("invalid puzzle JSON: {}", err);
+            exit(2);
+        }
+    };
+
+    match solve_outcome(puzzle) {
+        Ok(outcome) => {
+            println!("{}", serde_json::to_string_pretty(&outcome).unwrap());
+            if matches!(outcome, SolveOutcome::Unsolvable) {
+                exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("invalid puzzle: {}", err);
+            exit(2);
+        }
+    }
 }